@@ -1,12 +1,73 @@
+use crate::logged_command::{CommandLog, LoggedCommand};
+use crate::notify::NotifyConfig;
 use anyhow::{bail, Context, Result};
 use core::str::from_utf8;
 use regex::Regex;
 use serde::Deserialize;
+use std::collections::HashSet;
 use std::fs::read_to_string;
-use std::process::Stdio;
-use tokio::process::Command;
+use std::path::{Path, PathBuf};
 use tracing::{debug, warn};
 
+/// a single match rule used in `filter_patterns`/`replace_patterns`. A bare
+/// JSON string is compiled as a regex, preserving existing device config
+/// files unchanged; `{"exact": "..."}` instead matches (and replaces) a
+/// literal substring, skipping regex compilation entirely, which matters for
+/// the common case of stripping fixed banner lines and known tokens across
+/// thousands of lines
+#[derive(Deserialize, Clone, Debug)]
+#[serde(untagged)]
+enum MatchRule {
+    Implicit(String),
+    Explicit(ExplicitMatchRule),
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(rename_all = "snake_case")]
+enum ExplicitMatchRule {
+    Regex(String),
+    Exact(String),
+}
+
+/// a [`MatchRule`] compiled into a form ready for matching/replacing
+enum CompiledMatchRule {
+    Regex(Regex),
+    Exact(String),
+}
+
+impl CompiledMatchRule {
+    fn compile(rule: MatchRule) -> Result<Self> {
+        let pattern = match rule {
+            MatchRule::Implicit(pattern) => pattern,
+            MatchRule::Explicit(ExplicitMatchRule::Regex(pattern)) => pattern,
+            MatchRule::Explicit(ExplicitMatchRule::Exact(literal)) => {
+                return Ok(Self::Exact(literal))
+            }
+        };
+
+        Regex::new(&pattern)
+            .map(Self::Regex)
+            .with_context(|| format!("failed to compile regex: '{pattern}'"))
+    }
+
+    fn is_match(&self, line: &str) -> bool {
+        match self {
+            Self::Regex(regex) => regex.is_match(line),
+            Self::Exact(literal) => line.contains(literal.as_str()),
+        }
+    }
+
+    /// replaces every match in `line`; for `Regex` rules, `replacement` may
+    /// reference named capture groups (e.g. `$secret`) so only the captured
+    /// portion of the match is redacted
+    fn replace_all(&self, line: &str, replacement: &str) -> String {
+        match self {
+            Self::Regex(regex) => regex.replace_all(line, replacement).to_string(),
+            Self::Exact(literal) => line.replace(literal.as_str(), replacement),
+        }
+    }
+}
+
 /// configuration for filtering output from the expect script for this device
 #[derive(Deserialize, Clone, Debug)]
 #[serde(deny_unknown_fields)]
@@ -19,11 +80,11 @@ pub struct Filter {
     trim_lines_tail: usize,
     /// all lines that match one of these patterns are removed from the output
     #[serde(default)]
-    filter_patterns: Vec<String>,
+    filter_patterns: Vec<MatchRule>,
     /// all occurrences of the pattern (first tuple element) in a line are replaced
     /// with the second element of the tuple; for each tuple
     #[serde(default)]
-    replace_patterns: Vec<(String, String)>,
+    replace_patterns: Vec<(MatchRule, String)>,
 }
 
 /// all device specific parameters as well as an optional [`Filter`]
@@ -51,16 +112,122 @@ pub struct DeviceConfig {
     filter_config: Option<Filter>,
 }
 
+/// top-level document stored at `--devices`; accepted either as a bare array
+/// of devices (the original format) or as an object carrying the device
+/// array alongside an optional `notifications` section
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum DevicesDocument {
+    Bare(Vec<DeviceConfig>),
+    Full {
+        devices: Vec<DeviceConfig>,
+        #[serde(default)]
+        notifications: NotifyConfig,
+    },
+}
+
+/// resolves `config_path` to the list of JSON files it refers to: a single
+/// regular file is returned as-is, a directory is expanded to all `*.json`
+/// files directly inside it, and anything containing glob metacharacters
+/// (`*`, `?`, `[`) is expanded via [`glob::glob`]. In both the directory and
+/// glob cases the files are returned in sorted order so merging is stable.
+fn discover_device_files(config_path: &str) -> Result<Vec<PathBuf>> {
+    let path = Path::new(config_path);
+
+    if path.is_dir() {
+        let mut files: Vec<PathBuf> = std::fs::read_dir(path)
+            .with_context(|| format!("failed to read devices directory '{config_path}'"))?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().and_then(|ext| ext.to_str()) == Some("json"))
+            .collect();
+        files.sort();
+        return Ok(files);
+    }
+
+    if config_path.contains(['*', '?', '[']) {
+        let mut files = glob::glob(config_path)
+            .with_context(|| format!("invalid glob pattern '{config_path}'"))?
+            .collect::<std::result::Result<Vec<_>, _>>()
+            .with_context(|| format!("failed to resolve glob pattern '{config_path}'"))?;
+        files.sort();
+        return Ok(files);
+    }
+
+    Ok(vec![path.to_path_buf()])
+}
+
 impl DeviceConfig {
     /// reads the JSON configuration from `config_path` and deserializes it
     /// into a `Vec<DeviceConfig>` using `serde_json::from_str()`
     pub fn read_all_from_file(config_path: &str) -> Result<Vec<Self>> {
-        let config_json = read_to_string(config_path)?;
+        Ok(Self::read_devices_and_notify_config(config_path)?.0)
+    }
+
+    /// like [`Self::read_all_from_file`], but also returns the optional
+    /// `notifications` section so callers can dispatch commit notifications.
+    ///
+    /// `config_path` may be a single JSON file (original behavior), a
+    /// directory (every `*.json` file directly inside it is merged), or a
+    /// glob pattern such as `devices.d/*.json`. Devices from every discovered
+    /// file are merged into one flat list; a `host` that appears in more
+    /// than one file is an error naming the offending file.
+    pub fn read_devices_and_notify_config(config_path: &str) -> Result<(Vec<Self>, NotifyConfig)> {
+        let files = discover_device_files(config_path)?;
+        if files.is_empty() {
+            bail!("no device definition files found for '{config_path}'");
+        }
+
+        let mut all_devices = Vec::new();
+        let mut notify_config = NotifyConfig::default();
+        let mut seen_hosts = HashSet::new();
+
+        for file in &files {
+            let file = file.to_string_lossy();
+            let (devices, file_notify_config) = Self::read_single_file(&file)?;
+
+            for device in &devices {
+                if !seen_hosts.insert(device.host.clone()) {
+                    bail!("duplicate device host '{}' found in '{file}'", device.host);
+                }
+            }
+
+            // merge field-wise so splitting the `notifications` section's
+            // channels across files (e.g. `smtp` in one, `webhook` in
+            // another) doesn't silently drop whichever was set first
+            if file_notify_config.smtp.is_some() {
+                if notify_config.smtp.is_some() {
+                    bail!("duplicate 'notifications.smtp' section found in '{file}'");
+                }
+                notify_config.smtp = file_notify_config.smtp;
+            }
+            if file_notify_config.webhook.is_some() {
+                if notify_config.webhook.is_some() {
+                    bail!("duplicate 'notifications.webhook' section found in '{file}'");
+                }
+                notify_config.webhook = file_notify_config.webhook;
+            }
+
+            all_devices.extend(devices);
+        }
+
+        Ok((all_devices, notify_config))
+    }
 
-        let configs: Vec<DeviceConfig> = serde_json::from_str(&config_json)
+    /// reads and deserializes a single devices JSON document
+    fn read_single_file(config_path: &str) -> Result<(Vec<Self>, NotifyConfig)> {
+        let config_json = read_to_string(config_path)
+            .with_context(|| format!("failed to read '{config_path}'"))?;
+
+        let document: DevicesDocument = serde_json::from_str(&config_json)
             .with_context(|| format!("failed to deserialize JSON from '{config_path}'"))?;
 
-        Ok(configs)
+        Ok(match document {
+            DevicesDocument::Bare(devices) => (devices, NotifyConfig::default()),
+            DevicesDocument::Full {
+                devices,
+                notifications,
+            } => (devices, notifications),
+        })
     }
 
     /// constructs the path to the file into which the (filtered) device config dump
@@ -69,33 +236,33 @@ impl DeviceConfig {
         format!("{state_dir}/{}", self.host)
     }
 
-    /// executes the device's expect script and returns its filtered output
-    pub async fn into_filtered_dump(self, scripts_dir: &str) -> Result<String> {
+    /// the host address (IP or hostname) identifying this device
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    /// executes the device's expect script and returns its filtered output;
+    /// the invocation (argv, stdout, stderr and exit status) is recorded to
+    /// `log` regardless of whether it succeeds
+    pub async fn into_filtered_dump(
+        self,
+        scripts_dir: &str,
+        log: &mut CommandLog,
+    ) -> Result<String> {
         // path to the expect script for this model; assumed to
         let script_path = format!("{scripts_dir}/{}.exp", &self.model);
 
         // get optional filter closure for this device
         let maybe_filter = self.to_filter()?;
 
+        debug!("running expect script: {script_path}");
+
         // expect script with parameters
-        let mut cmd = Command::new(&script_path);
-        cmd.args(self.into_expect_args())
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped());
-
-        debug!("running script: {cmd:?}");
-
-        let child = cmd
-            .spawn()
-            .with_context(|| format!("failed to run expect script {script_path}"))?;
-        let output = child.wait_with_output().await?;
-
-        if !output.status.success() {
-            bail!(
-                "expect script failed {script_path}:\n{}",
-                from_utf8(&output.stderr).unwrap_or("<invalid utf8>")
-            );
-        }
+        let output = LoggedCommand::new(&script_path)
+            .args(self.into_expect_args())
+            .execute(log)
+            .await
+            .with_context(|| format!("expect script failed: {script_path}"))?;
 
         let stdout_str = from_utf8(&output.stdout)?;
 
@@ -120,23 +287,19 @@ impl DeviceConfig {
         // compiling regexes from user-provided patterns may fail
         // so we compile them outside the filter closure
 
-        // regexes for lines that will be removed
-        let filter_regexes = filter_config
+        // match rules for lines that will be removed
+        let filter_rules = filter_config
             .filter_patterns
-            .iter()
-            .map(|regex| {
-                Regex::new(regex).with_context(|| format!("failed to compile regex: '{regex}'"))
-            })
+            .into_iter()
+            .map(CompiledMatchRule::compile)
             .collect::<Result<Vec<_>>>()?;
 
-        // regexes and corresponding replacements as tuples
+        // match rules and corresponding replacements as tuples
         let replacements = filter_config
             .replace_patterns
             .into_iter()
-            .map(|(regex, replacement)| {
-                Regex::new(&regex)
-                    .with_context(|| format!("failed to compile regex: '{regex}'"))
-                    .map(|re| (re, replacement))
+            .map(|(rule, replacement)| {
+                CompiledMatchRule::compile(rule).map(|rule| (rule, replacement))
             })
             .collect::<Result<Vec<_>>>()?;
 
@@ -150,13 +313,13 @@ impl DeviceConfig {
                 // replace patterns from `replacement_patterns`
                 .map(|line| {
                     let mut line = line.to_owned();
-                    for &(ref regex, ref replacement) in &replacements {
-                        line = regex.replace_all(&line, replacement).to_string();
+                    for (rule, replacement) in &replacements {
+                        line = rule.replace_all(&line, replacement);
                     }
                     line
                 })
                 // remove lines containing any of `filter_patterns`
-                .filter(|line| !filter_regexes.iter().any(|re| re.is_match(line)))
+                .filter(|line| !filter_rules.iter().any(|rule| rule.is_match(line)))
                 // remove trailing whitespace
                 .map(|line| line.trim_end().to_owned())
                 .collect::<Vec<String>>();
@@ -189,3 +352,98 @@ impl DeviceConfig {
             .collect()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// creates a fresh, empty temporary directory for a single test and
+    /// returns its path; reused across the `discover_device_files` tests
+    /// since the function only reads files from disk
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "rusted-test-{name}-{:?}",
+            std::thread::current().id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn discover_device_files_returns_single_file_as_is() {
+        let files = discover_device_files("devices.json").unwrap();
+        assert_eq!(files, vec![PathBuf::from("devices.json")]);
+    }
+
+    #[test]
+    fn discover_device_files_expands_directory_to_sorted_json_files() {
+        let dir = temp_dir("dir");
+        std::fs::write(dir.join("b.json"), "[]").unwrap();
+        std::fs::write(dir.join("a.json"), "[]").unwrap();
+        std::fs::write(dir.join("readme.txt"), "not json").unwrap();
+
+        let files = discover_device_files(dir.to_str().unwrap()).unwrap();
+
+        assert_eq!(files, vec![dir.join("a.json"), dir.join("b.json")]);
+    }
+
+    #[test]
+    fn discover_device_files_expands_glob_pattern_sorted() {
+        let dir = temp_dir("glob");
+        std::fs::write(dir.join("b.json"), "[]").unwrap();
+        std::fs::write(dir.join("a.json"), "[]").unwrap();
+
+        let pattern = dir.join("*.json");
+        let files = discover_device_files(pattern.to_str().unwrap()).unwrap();
+
+        assert_eq!(files, vec![dir.join("a.json"), dir.join("b.json")]);
+    }
+
+    #[test]
+    fn compiled_match_rule_implicit_string_is_regex() {
+        let rule =
+            CompiledMatchRule::compile(MatchRule::Implicit("^secret:.*$".to_owned())).unwrap();
+
+        assert!(rule.is_match("secret: hunter2"));
+        assert!(!rule.is_match("not a secret line"));
+    }
+
+    #[test]
+    fn compiled_match_rule_exact_matches_literal_substring_only() {
+        let rule = CompiledMatchRule::compile(MatchRule::Explicit(ExplicitMatchRule::Exact(
+            "a.b".to_owned(),
+        )))
+        .unwrap();
+
+        // a regex would treat `.` as "any character"; exact must not
+        assert!(rule.is_match("contains a.b literally"));
+        assert!(!rule.is_match("contains aXb instead"));
+    }
+
+    #[test]
+    fn compiled_match_rule_regex_replace_all_supports_named_capture_redaction() {
+        let rule = CompiledMatchRule::compile(MatchRule::Explicit(ExplicitMatchRule::Regex(
+            "(?P<prefix>password: ).*".to_owned(),
+        )))
+        .unwrap();
+
+        assert_eq!(
+            rule.replace_all("password: hunter2", "${prefix}<REDACTED>"),
+            "password: <REDACTED>"
+        );
+    }
+
+    #[test]
+    fn compiled_match_rule_exact_replace_all_replaces_every_occurrence() {
+        let rule = CompiledMatchRule::compile(MatchRule::Explicit(ExplicitMatchRule::Exact(
+            "token".to_owned(),
+        )))
+        .unwrap();
+
+        assert_eq!(
+            rule.replace_all("token=token123token", "REDACTED"),
+            "REDACTED=REDACTED123REDACTED"
+        );
+    }
+}
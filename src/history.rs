@@ -0,0 +1,192 @@
+use anyhow::{Context, Result};
+use rusqlite::{params, Connection};
+use std::time::{Duration, SystemTime};
+
+/// per-host summary derived from the run history, as printed by `rusted status`
+pub struct HostStatus {
+    pub host: String,
+    pub last_success: Option<String>,
+    pub consecutive_failures: u32,
+    pub last_changed: Option<String>,
+}
+
+/// durable, SQLite-backed record of every device run (fetch + commit), so a
+/// device that has silently failed for days is distinguishable from a
+/// healthy one without having to parse logs
+pub struct RunHistory {
+    conn: Connection,
+}
+
+impl RunHistory {
+    /// opens (creating if necessary) the state database at `db_path`
+    pub fn open(db_path: &str) -> Result<Self> {
+        let conn = Connection::open(db_path)
+            .with_context(|| format!("failed to open state database '{db_path}'"))?;
+
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS runs (
+                id          INTEGER PRIMARY KEY AUTOINCREMENT,
+                host        TEXT NOT NULL,
+                started_at  TEXT NOT NULL,
+                success     INTEGER NOT NULL,
+                duration_ms INTEGER NOT NULL,
+                error       TEXT,
+                changed     INTEGER NOT NULL
+            );",
+        )
+        .context("failed to initialize state database schema")?;
+
+        Ok(Self { conn })
+    }
+
+    /// records the outcome of fetching a single device's config. `changed` is
+    /// initially always `false`; [`Self::mark_changed`] flips it once
+    /// `update_git_repo` observes a non-empty git diff for that host
+    pub fn record_run(
+        &self,
+        host: &str,
+        success: bool,
+        duration: Duration,
+        error: Option<&str>,
+    ) -> Result<()> {
+        self.conn
+            .execute(
+                "INSERT INTO runs (host, started_at, success, duration_ms, error, changed)
+                 VALUES (?1, ?2, ?3, ?4, ?5, 0)",
+                params![
+                    host,
+                    humantime::format_rfc3339(SystemTime::now()).to_string(),
+                    success,
+                    duration.as_millis() as i64,
+                    error,
+                ],
+            )
+            .context("failed to record run in state database")?;
+
+        Ok(())
+    }
+
+    /// marks the most recent run for `host` as having produced a changed
+    /// (non-empty diff) config
+    pub fn mark_changed(&self, host: &str) -> Result<()> {
+        self.conn
+            .execute(
+                "UPDATE runs SET changed = 1
+                 WHERE id = (SELECT id FROM runs WHERE host = ?1 ORDER BY id DESC LIMIT 1)",
+                params![host],
+            )
+            .context("failed to mark run as changed in state database")?;
+
+        Ok(())
+    }
+
+    /// builds a per-host status report: the timestamp of the last successful
+    /// fetch, the number of consecutive failures since then, and the
+    /// timestamp the config was last observed to actually change
+    pub fn status_report(&self) -> Result<Vec<HostStatus>> {
+        let mut host_stmt = self
+            .conn
+            .prepare("SELECT DISTINCT host FROM runs ORDER BY host")?;
+        let hosts = host_stmt
+            .query_map([], |row| row.get::<_, String>(0))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        let mut run_stmt = self.conn.prepare(
+            "SELECT started_at, success, changed FROM runs WHERE host = ?1 ORDER BY id DESC",
+        )?;
+
+        let mut statuses = Vec::with_capacity(hosts.len());
+        for host in hosts {
+            let runs = run_stmt
+                .query_map(params![host], |row| {
+                    Ok((
+                        row.get::<_, String>(0)?,
+                        row.get::<_, bool>(1)?,
+                        row.get::<_, bool>(2)?,
+                    ))
+                })?
+                .collect::<rusqlite::Result<Vec<_>>>()?;
+
+            let last_success = runs
+                .iter()
+                .find(|(_, success, _)| *success)
+                .map(|(started_at, ..)| started_at.clone());
+            let consecutive_failures =
+                runs.iter().take_while(|(_, success, _)| !success).count() as u32;
+            let last_changed = runs
+                .iter()
+                .find(|(_, _, changed)| *changed)
+                .map(|(started_at, ..)| started_at.clone());
+
+            statuses.push(HostStatus {
+                host,
+                last_success,
+                consecutive_failures,
+                last_changed,
+            });
+        }
+
+        Ok(statuses)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn status_report_is_empty_without_any_runs() {
+        let history = RunHistory::open(":memory:").unwrap();
+        assert!(history.status_report().unwrap().is_empty());
+    }
+
+    #[test]
+    fn status_report_counts_only_trailing_consecutive_failures() {
+        let history = RunHistory::open(":memory:").unwrap();
+
+        // oldest first: a success, then two failures in a row
+        history
+            .record_run("host-a", true, Duration::from_secs(1), None)
+            .unwrap();
+        history
+            .record_run("host-a", false, Duration::from_secs(1), Some("timeout"))
+            .unwrap();
+        history
+            .record_run("host-a", false, Duration::from_secs(1), Some("timeout"))
+            .unwrap();
+
+        let statuses = history.status_report().unwrap();
+        assert_eq!(statuses.len(), 1);
+        assert_eq!(statuses[0].host, "host-a");
+        assert_eq!(statuses[0].consecutive_failures, 2);
+        assert!(statuses[0].last_success.is_some());
+    }
+
+    #[test]
+    fn status_report_resets_consecutive_failures_after_a_success() {
+        let history = RunHistory::open(":memory:").unwrap();
+
+        history
+            .record_run("host-a", false, Duration::from_secs(1), Some("timeout"))
+            .unwrap();
+        history
+            .record_run("host-a", true, Duration::from_secs(1), None)
+            .unwrap();
+
+        let statuses = history.status_report().unwrap();
+        assert_eq!(statuses[0].consecutive_failures, 0);
+    }
+
+    #[test]
+    fn status_report_reflects_mark_changed_on_the_most_recent_run() {
+        let history = RunHistory::open(":memory:").unwrap();
+
+        history
+            .record_run("host-a", true, Duration::from_secs(1), None)
+            .unwrap();
+        assert!(history.status_report().unwrap()[0].last_changed.is_none());
+
+        history.mark_changed("host-a").unwrap();
+        assert!(history.status_report().unwrap()[0].last_changed.is_some());
+    }
+}
@@ -0,0 +1,170 @@
+use anyhow::{bail, Context, Result};
+use core::str::from_utf8;
+use std::fs::{create_dir_all, OpenOptions};
+use std::io::Write;
+#[cfg(unix)]
+use std::os::unix::process::ExitStatusExt;
+use std::process::{ExitStatus, Output, Stdio};
+use std::time::SystemTime;
+use tokio::process::Command;
+
+/// renders an `ExitStatus` in a form that is identical across platforms,
+/// unlike `ExitStatus`'s `Display` impl (`exit status: 0` on some platforms,
+/// `exit code: 0` on others)
+fn format_exit_status(status: &ExitStatus) -> String {
+    if let Some(code) = status.code() {
+        return format!("exit code: {code}");
+    }
+
+    #[cfg(unix)]
+    if let Some(signal) = status.signal() {
+        return format!("signal: {signal}");
+    }
+
+    "exit code: unknown".to_owned()
+}
+
+/// an open, append-only command log for a single device (or the shared git
+/// repository); every [`LoggedCommand::execute`] call writes one entry here,
+/// regardless of whether the command succeeded
+pub struct CommandLog {
+    file: std::fs::File,
+}
+
+impl CommandLog {
+    /// opens (creating if necessary) the log file `{log_dir}/{name}.log`
+    pub fn open(log_dir: &str, name: &str) -> Result<Self> {
+        create_dir_all(log_dir).with_context(|| format!("failed to create log_dir '{log_dir}'"))?;
+
+        let path = format!("{log_dir}/{name}.log");
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .with_context(|| format!("failed to open command log '{path}'"))?;
+
+        Ok(Self { file })
+    }
+
+    fn write_entry(
+        &mut self,
+        argv: &[String],
+        status: &str,
+        stdout: &str,
+        stderr: &str,
+    ) -> Result<()> {
+        writeln!(
+            self.file,
+            "=== {} ===",
+            humantime::format_rfc3339(SystemTime::now())
+        )?;
+        writeln!(self.file, "argv: {argv:?}")?;
+        writeln!(self.file, "{status}")?;
+        writeln!(self.file, "--- stdout ---\n{stdout}")?;
+        writeln!(self.file, "--- stderr ---\n{stderr}")?;
+        writeln!(self.file)?;
+
+        Ok(())
+    }
+}
+
+/// a single external command invocation, built up the same way as
+/// `std::process::Command`/`tokio::process::Command`, but always recorded to
+/// a [`CommandLog`] on completion
+pub struct LoggedCommand {
+    program: String,
+    args: Vec<String>,
+    current_dir: Option<String>,
+}
+
+impl LoggedCommand {
+    pub fn new(program: impl Into<String>) -> Self {
+        Self {
+            program: program.into(),
+            args: Vec::new(),
+            current_dir: None,
+        }
+    }
+
+    pub fn args<I, S>(mut self, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.args.extend(args.into_iter().map(Into::into));
+        self
+    }
+
+    pub fn current_dir(mut self, dir: impl Into<String>) -> Self {
+        self.current_dir = Some(dir.into());
+        self
+    }
+
+    /// runs the command to completion, writing a durable entry to `log`
+    /// (full argv, timestamp, captured stdout/stderr and the exit status)
+    /// whether it succeeds or fails, then returns its `Output`
+    pub async fn execute(self, log: &mut CommandLog) -> Result<Output> {
+        let argv: Vec<String> = std::iter::once(self.program.clone())
+            .chain(self.args.clone())
+            .collect();
+
+        let mut cmd = Command::new(&self.program);
+        cmd.args(&self.args)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+
+        if let Some(dir) = &self.current_dir {
+            cmd.current_dir(dir);
+        }
+
+        let output = match cmd.spawn() {
+            Ok(child) => child.wait_with_output().await,
+            Err(e) => Err(e),
+        };
+        let output = match output {
+            Ok(output) => output,
+            Err(e) => {
+                log.write_entry(&argv, &format!("failed to run: {e}"), "", "")
+                    .context("failed to write command log entry")?;
+                return Err(e).with_context(|| format!("failed to run '{}'", self.program));
+            }
+        };
+
+        let stdout = from_utf8(&output.stdout).unwrap_or("<invalid utf8>");
+        let stderr = from_utf8(&output.stderr).unwrap_or("<invalid utf8>");
+        let status = format_exit_status(&output.status);
+
+        log.write_entry(&argv, &status, stdout, stderr)
+            .context("failed to write command log entry")?;
+
+        if !output.status.success() {
+            bail!("command {argv:?} failed ({status}):\nstderr:\n{stderr}\nstdout:\n{stdout}");
+        }
+
+        Ok(output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn format_exit_status_renders_exit_code() {
+        let status = ExitStatus::from_raw(0);
+        assert_eq!(format_exit_status(&status), "exit code: 0");
+
+        let status = ExitStatus::from_raw(2 << 8);
+        assert_eq!(format_exit_status(&status), "exit code: 2");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn format_exit_status_renders_signal() {
+        // low byte holds the signal number and the 0x80 "core dumped" bit is
+        // unset, which is how `ExitStatus::signal()` recognizes a signal exit
+        let status = ExitStatus::from_raw(9);
+        assert_eq!(format_exit_status(&status), "signal: 9");
+    }
+}
@@ -1,20 +1,43 @@
 mod devices;
+mod history;
+mod logged_command;
+mod notify;
 
 use anyhow::{anyhow, bail, Context, Error, Result};
-use clap::Parser;
-use core::str::from_utf8;
+use clap::{Parser, Subcommand};
 use devices::DeviceConfig;
+use history::RunHistory;
+use logged_command::{CommandLog, LoggedCommand};
+use notify::NotifyConfig;
 use std::fs::write;
 use std::io::stderr;
 use std::path::Path;
-use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use tokio::runtime::Runtime;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::Semaphore;
 use tracing::{debug, error, info};
 use tracing_subscriber::{filter::LevelFilter, EnvFilter};
 
-/// rusted config, command line parameter parsing is done using `clap_derive`
+/// rusted: fetches and version-controls network device running-configs.
+/// command line parameter parsing is done using `clap_derive`
 #[derive(Parser, Debug)]
-struct Config {
+struct Cli {
+    #[clap(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// fetch device configs and commit/push changes (the default workflow)
+    Run(RunConfig),
+    /// print a fleet-health table from the state database
+    Status(StatusConfig),
+}
+
+#[derive(Parser, Debug)]
+struct RunConfig {
     /// directory that contains expect scripts for each device model
     #[clap(short, long, default_value = "expect_scripts")]
     expect_scripts_dir: String,
@@ -27,6 +50,79 @@ struct Config {
     /// disable pushing to the default remote repository after committing
     #[clap(long)]
     no_push: bool,
+    /// disable per-commit notifications even if a `notifications` section is
+    /// configured in the devices JSON
+    #[clap(long)]
+    no_notify: bool,
+    /// run continuously, repeating the fetch-commit-push cycle instead of
+    /// exiting after a single run; requires `--interval` (there is no
+    /// implicit default cadence)
+    #[clap(long, requires = "interval")]
+    daemon: bool,
+    /// polling interval for daemon mode (e.g. `30m`, `1h`); implies `--daemon`
+    #[clap(long, value_parser = humantime::parse_duration)]
+    interval: Option<Duration>,
+    /// directory to write per-device command logs to; defaults to
+    /// `{state_dir}/logs`
+    #[clap(long)]
+    log_dir: Option<String>,
+    /// path to the SQLite run history database; defaults to
+    /// `{state_dir}/rusted.db3`
+    #[clap(long)]
+    state_db: Option<String>,
+    /// maximum number of devices fetched concurrently; defaults to the
+    /// number of available CPUs. Pass `0` for unlimited concurrency
+    #[clap(long)]
+    max_concurrent: Option<usize>,
+}
+
+impl RunConfig {
+    /// directory that command logs are written to
+    fn log_dir(&self) -> String {
+        self.log_dir
+            .clone()
+            .unwrap_or_else(|| format!("{}/logs", self.state_dir))
+    }
+
+    /// path to the SQLite run history database
+    fn state_db(&self) -> String {
+        self.state_db
+            .clone()
+            .unwrap_or_else(|| format!("{}/rusted.db3", self.state_dir))
+    }
+
+    /// permitted number of concurrent device fetches, or `None` for unlimited
+    fn max_concurrent(&self) -> Option<usize> {
+        match self.max_concurrent {
+            Some(0) => None,
+            Some(n) => Some(n),
+            None => Some(
+                std::thread::available_parallelism()
+                    .map(|n| n.get())
+                    .unwrap_or(4),
+            ),
+        }
+    }
+}
+
+#[derive(Parser, Debug)]
+struct StatusConfig {
+    /// location of the git repository containing the fetched device configurations
+    #[clap(long, default_value = "configs")]
+    state_dir: String,
+    /// path to the SQLite run history database; defaults to
+    /// `{state_dir}/rusted.db3`
+    #[clap(long)]
+    state_db: Option<String>,
+}
+
+impl StatusConfig {
+    /// path to the SQLite run history database
+    fn state_db(&self) -> String {
+        self.state_db
+            .clone()
+            .unwrap_or_else(|| format!("{}/rusted.db3", self.state_dir))
+    }
 }
 
 /// initializes the tracing subscriber, sets the default log level to `INFO`.
@@ -55,6 +151,35 @@ async fn update_device_config_file(
     device: DeviceConfig,
     expect_scripts_dir: String,
     state_dir: String,
+    log_dir: String,
+    history: Arc<Mutex<RunHistory>>,
+) -> Result<()> {
+    let host = device.host().to_owned();
+    let started = Instant::now();
+
+    let result =
+        update_device_config_file_inner(device_nr, device, expect_scripts_dir, state_dir, log_dir)
+            .await;
+
+    let record_result = history.lock().unwrap().record_run(
+        &host,
+        result.is_ok(),
+        started.elapsed(),
+        result.as_ref().err().map(|e| format!("{e:#}")).as_deref(),
+    );
+    if let Err(e) = record_result {
+        error!("Device {device_nr}: failed to record run history: {e:#}");
+    }
+
+    result
+}
+
+async fn update_device_config_file_inner(
+    device_nr: usize,
+    device: DeviceConfig,
+    expect_scripts_dir: String,
+    state_dir: String,
+    log_dir: String,
 ) -> Result<()> {
     // construct path to config dump file for this device
     let dump_file = device.to_config_dump_path(&state_dir);
@@ -64,104 +189,189 @@ async fn update_device_config_file(
         bail!("state_dir {state_dir} does not exist");
     }
 
+    let mut log = CommandLog::open(&log_dir, device.host())?;
+
     info!("Device {device_nr}: fetching running-config");
     // consume device to acquire its filtered config dump
-    let dump = device.into_filtered_dump(&expect_scripts_dir).await?;
+    let dump = device
+        .into_filtered_dump(&expect_scripts_dir, &mut log)
+        .await?;
 
     info!("Device {device_nr}: writing running-config to '{dump_file}'");
     // write filtered config dump to previously constructed file location
     write(dump_file, dump.as_bytes()).map_err(Error::msg)
 }
 
-/// invokes `git` with the specified `subcmd` and further `args` in `workdir`
+/// invokes `git` with the specified `subcmd` and further `args` in `workdir`,
+/// recording the invocation to `log`.
 /// `with_output` controls whether or not the result contains Some(stdout) of the
 /// invoked command or None
-fn git_subcommand(
+async fn git_subcommand(
     subcmd: &str,
     workdir: &str,
     args: &[&str],
     with_output: bool,
+    log: &mut CommandLog,
 ) -> Result<Option<String>> {
-    let child = Command::new("git")
-        .arg(subcmd)
-        .args(args)
+    let output = LoggedCommand::new("git")
+        .args(std::iter::once(subcmd).chain(args.iter().copied()))
         .current_dir(workdir)
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .spawn()
+        .execute(log)
+        .await
         .context("git command failed")?;
 
-    let output = child.wait_with_output()?;
-    if !output.status.success() {
-        bail!(
-            "git ls-files failed:\nstderr:\n{}\nstdout:\n{}",
-            from_utf8(&output.stderr).unwrap_or("<invalid utf8>"),
-            from_utf8(&output.stdout).unwrap_or("<invalid utf8>")
-        )
-    }
-
     if with_output {
-        let stdout_str = from_utf8(&output.stdout)?;
+        let stdout_str = core::str::from_utf8(&output.stdout)?;
         Ok(Some(stdout_str.to_owned()))
     } else {
         Ok(None)
     }
 }
 
-/// iterates over all modified and added files in `state_dir`,
-/// then creates one commit per file and optionally pushes changes
-/// to the default remote for the current branch
-fn update_git_repo(state_dir: &str, no_push: bool) -> Result<()> {
+/// returns `path`'s location relative to `state_dir`, or `None` if `path`
+/// isn't actually nested under `state_dir` (in which case `git ls-files`
+/// inside `state_dir` would never surface it in the first place)
+fn relative_to_state_dir(state_dir: &str, path: &str) -> Option<String> {
+    Path::new(path)
+        .strip_prefix(state_dir)
+        .ok()
+        .map(|rel| rel.to_string_lossy().into_owned())
+}
+
+/// iterates over all modified and added files in `state_dir`, then creates one
+/// commit per file, dispatches a notification carrying that file's diff (if
+/// `notify_config` configures any channel), and optionally pushes changes to
+/// the default remote for the current branch
+async fn update_git_repo(
+    state_dir: &str,
+    no_push: bool,
+    notify_config: &NotifyConfig,
+    log_dir: &str,
+    state_db: &str,
+    history: &Mutex<RunHistory>,
+) -> Result<()> {
+    let mut repo_log = CommandLog::open(log_dir, "_repository")?;
+
     let changed_files = git_subcommand(
         "ls-files",
         state_dir,
         vec!["--modified", "--others", "--exclude-standard"].as_ref(),
         true,
+        &mut repo_log,
     )
+    .await
     .context("failed to list changed files")?
     .ok_or_else(|| anyhow!("this should never happen"))?;
 
+    // `log_dir`/`state_db` default to paths under `state_dir` for operator
+    // convenience, but the command logs and run-history database they hold
+    // are not device configs; without this, every run would commit/notify
+    // on them as if they were
+    let excluded_log_dir = relative_to_state_dir(state_dir, log_dir);
+    let excluded_state_db = relative_to_state_dir(state_dir, state_db);
+
     for file in changed_files.lines() {
+        let is_log_dir_entry = match &excluded_log_dir {
+            Some(dir) => file == dir || file.starts_with(&format!("{dir}/")),
+            None => false,
+        };
+        let is_state_db_entry = excluded_state_db.as_deref() == Some(file);
+        if is_log_dir_entry || is_state_db_entry {
+            debug!("skipping '{file}': command log / state database, not a device config");
+            continue;
+        }
+
         info!("commiting changes to file '{state_dir}/{file}'");
 
-        git_subcommand("add", state_dir, vec![file].as_ref(), false)?;
+        // the file name matches the device host (see `to_config_dump_path`),
+        // so its git operations are recorded alongside that device's own log
+        let mut log = CommandLog::open(log_dir, file)?;
+
+        git_subcommand("add", state_dir, vec![file].as_ref(), false, &mut log).await?;
+
+        // captured before the commit so the notification carries the diff
+        // that is about to be committed
+        let diff = git_subcommand(
+            "diff",
+            state_dir,
+            vec!["--staged", "--", file].as_ref(),
+            true,
+            &mut log,
+        )
+        .await?
+        .unwrap_or_default();
+
         git_subcommand(
             "commit",
             state_dir,
             vec!["--message", format!("Update {file}").as_ref()].as_ref(),
             false,
-        )?;
+            &mut log,
+        )
+        .await?;
+
+        notify::notify_commit(notify_config, file, &diff).await;
+
+        // the file name matches the device host; record that this run
+        // actually changed the device's config
+        if !diff.is_empty() {
+            if let Err(e) = history.lock().unwrap().mark_changed(file) {
+                error!("failed to mark '{file}' as changed in run history: {e:#}");
+            }
+        }
     }
 
     if !no_push {
-        git_subcommand("push", state_dir, &[], false)?;
+        git_subcommand("push", state_dir, &[], false, &mut repo_log).await?;
     }
 
     Ok(())
 }
 
-fn main() -> Result<()> {
-    init_tracing()?;
+/// runs a single fetch-commit-push cycle: re-reads `config.devices` so
+/// configuration changes are picked up without a restart, fetches every
+/// device's running-config, then commits and pushes whatever changed
+async fn run_once(config: &RunConfig) -> Result<()> {
+    let (devices, notify_config) = DeviceConfig::read_devices_and_notify_config(&config.devices)?;
+    let notify_config = if config.no_notify {
+        NotifyConfig::default()
+    } else {
+        notify_config
+    };
 
-    let config = Config::parse();
-    debug!("{config:?}");
+    let history = Arc::new(Mutex::new(RunHistory::open(&config.state_db())?));
 
-    let devices = DeviceConfig::read_all_from_file(&config.devices)?;
+    // `None` permits unlimited concurrent fetches
+    let semaphore = config.max_concurrent().map(|n| Arc::new(Semaphore::new(n)));
 
     info!("fetching configs for {} devices", devices.len());
 
-    let rt = Runtime::new()?;
-
     let mut tasks = vec![];
-    // spawns a task on the runtime `rt` for each configured device.
+    // spawns a task for each configured device.
     // an index is assigned to each task to identify async log output
     for (i, device) in devices.into_iter().enumerate() {
         let scripts_dir = config.expect_scripts_dir.clone();
         let state_dir = config.state_dir.clone();
+        let log_dir = config.log_dir();
+        let history = Arc::clone(&history);
+        let semaphore = semaphore.clone();
 
-        tasks.push(rt.spawn(async move {
+        tasks.push(tokio::spawn(async move {
             let idx = i + 1; // devices start at 1 :)
-            update_device_config_file(idx, device, scripts_dir, state_dir)
+
+            // held for the duration of the fetch; dropped (releasing the
+            // permit) once this task completes
+            let _permit = match &semaphore {
+                Some(semaphore) => Some(
+                    semaphore
+                        .acquire_owned()
+                        .await
+                        .expect("semaphore is never closed"),
+                ),
+                None => None,
+            };
+
+            update_device_config_file(idx, device, scripts_dir, state_dir, log_dir, history)
                 .await
                 .map_err(|e| {
                     // log error when it occurs
@@ -171,8 +381,8 @@ fn main() -> Result<()> {
         }));
     }
 
-    let tasks_failed: bool = rt
-        .block_on(async { futures::future::join_all(tasks).await })
+    let tasks_failed: bool = futures::future::join_all(tasks)
+        .await
         .iter()
         .any(|res| match res {
             Ok(Err(_)) => true, // contained error was already logged above
@@ -183,7 +393,15 @@ fn main() -> Result<()> {
             _ => false,
         });
 
-    update_git_repo(&config.state_dir, config.no_push)?;
+    update_git_repo(
+        &config.state_dir,
+        config.no_push,
+        &notify_config,
+        &config.log_dir(),
+        &config.state_db(),
+        &history,
+    )
+    .await?;
 
     // TODO maybe exit before updating the git repo if any task failed?
     if tasks_failed {
@@ -192,3 +410,80 @@ fn main() -> Result<()> {
 
     Ok(())
 }
+
+/// repeats [`run_once`] on `interval`, re-reading `config.devices` on every
+/// tick, until SIGTERM or SIGINT is received. The signal is only acted on
+/// between cycles, so an in-flight cycle is always allowed to finish
+/// (fetch, commit, push) before the daemon exits
+async fn run_daemon(config: &RunConfig, interval: Duration) -> Result<()> {
+    info!("starting daemon mode with an interval of {interval:?}");
+
+    // registered once up front so a signal received while a cycle is still
+    // running is not missed, even though it's only acted on afterwards
+    let mut sigterm = signal(SignalKind::terminate())?;
+    let mut sigint = signal(SignalKind::interrupt())?;
+
+    loop {
+        if let Err(e) = run_once(config).await {
+            error!("daemon cycle failed: {e:#}");
+        }
+
+        tokio::select! {
+            () = tokio::time::sleep(interval) => {}
+            _ = sigint.recv() => {
+                info!("received SIGINT, shutting down");
+                break;
+            }
+            _ = sigterm.recv() => {
+                info!("received SIGTERM, shutting down");
+                break;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// prints a table of each host's last successful fetch, consecutive failure
+/// count, and last-changed time, read from the state database
+fn print_status(config: &StatusConfig) -> Result<()> {
+    let history = RunHistory::open(&config.state_db())?;
+    let statuses = history.status_report()?;
+
+    println!(
+        "{:<24} {:<24} {:<10} {:<24}",
+        "HOST", "LAST SUCCESS", "FAILURES", "LAST CHANGED"
+    );
+    for status in statuses {
+        println!(
+            "{:<24} {:<24} {:<10} {:<24}",
+            status.host,
+            status.last_success.as_deref().unwrap_or("never"),
+            status.consecutive_failures,
+            status.last_changed.as_deref().unwrap_or("never"),
+        );
+    }
+
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    init_tracing()?;
+
+    let cli = Cli::parse();
+    debug!("{cli:?}");
+
+    match cli.command {
+        Command::Run(config) => {
+            let rt = Runtime::new()?;
+
+            // `--daemon` requires `--interval` (enforced by clap), so
+            // `--interval` alone is sufficient to decide the mode here
+            match config.interval {
+                Some(interval) => rt.block_on(run_daemon(&config, interval)),
+                None => rt.block_on(run_once(&config)),
+            }
+        }
+        Command::Status(config) => print_status(&config),
+    }
+}
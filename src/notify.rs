@@ -0,0 +1,130 @@
+use anyhow::{bail, Context, Result};
+use lettre::message::Message;
+use lettre::transport::smtp::SmtpTransport;
+use lettre::Transport;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+/// SMTP settings for "diff mail" style commit notifications
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct SmtpNotifyConfig {
+    /// address that notification emails are sent from
+    pub from: String,
+    /// addresses that notification emails are sent to
+    pub recipients: Vec<String>,
+    /// hostname (optionally `host:port`) of the SMTP relay to use
+    pub relay_host: String,
+}
+
+/// webhook settings for commit notifications
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct WebhookNotifyConfig {
+    /// URL that the webhook payload is POSTed to
+    pub url: String,
+}
+
+/// optional `notifications` section of the devices JSON document;
+/// enables per-commit diff notifications when present
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct NotifyConfig {
+    /// email notifications via SMTP relay
+    #[serde(default)]
+    pub smtp: Option<SmtpNotifyConfig>,
+    /// notifications via generic HTTP webhook
+    #[serde(default)]
+    pub webhook: Option<WebhookNotifyConfig>,
+}
+
+/// JSON payload POSTed to the configured webhook for each commit
+#[derive(Serialize, Debug)]
+struct WebhookPayload<'a> {
+    file: &'a str,
+    diff: &'a str,
+}
+
+/// dispatches a "diff mail" style notification for a single commit to every
+/// configured channel; a failing channel is logged and does not prevent the
+/// other channels (or the surrounding commit/push) from proceeding
+pub async fn notify_commit(config: &NotifyConfig, file: &str, diff: &str) {
+    if let Some(smtp) = &config.smtp {
+        // `send_email` makes a blocking network call to the SMTP relay; run
+        // it on a blocking-pool thread so a slow/unreachable relay can't
+        // stall a worker thread the bounded device fetches also rely on
+        let smtp = smtp.clone();
+        let file_owned = file.to_owned();
+        let diff_owned = diff.to_owned();
+
+        match tokio::task::spawn_blocking(move || send_email(&smtp, &file_owned, &diff_owned)).await
+        {
+            Ok(Err(e)) => warn!("failed to send notification email for '{file}': {e:#}"),
+            Err(e) => warn!("notification email task for '{file}' panicked: {e:#}"),
+            Ok(Ok(())) => {}
+        }
+    }
+
+    if let Some(webhook) = &config.webhook {
+        if let Err(e) = send_webhook(webhook, file, diff).await {
+            warn!("failed to send webhook notification for '{file}': {e:#}");
+        }
+    }
+}
+
+/// sends the diff as the body of an email, reusing the `Update {file}` subject
+/// convention used for the commit message itself
+fn send_email(config: &SmtpNotifyConfig, file: &str, diff: &str) -> Result<()> {
+    let mut builder = Message::builder()
+        .from(config.from.parse().context("invalid 'from' address")?)
+        .subject(format!("Update {file}"));
+
+    for recipient in &config.recipients {
+        builder = builder.to(recipient
+            .parse()
+            .with_context(|| format!("invalid recipient address '{recipient}'"))?);
+    }
+
+    let email = builder
+        .body(diff.to_owned())
+        .context("failed to build notification email")?;
+
+    let mailer = SmtpTransport::relay(&config.relay_host)
+        .with_context(|| format!("failed to configure SMTP relay '{}'", config.relay_host))?
+        .build();
+
+    mailer
+        .send(&email)
+        .context("failed to send notification email")?;
+
+    debug!(
+        "sent notification email for '{file}' to {:?}",
+        config.recipients
+    );
+
+    Ok(())
+}
+
+/// POSTs the changed file and unified diff as JSON to the webhook URL
+async fn send_webhook(config: &WebhookNotifyConfig, file: &str, diff: &str) -> Result<()> {
+    let payload = WebhookPayload { file, diff };
+
+    let response = reqwest::Client::new()
+        .post(&config.url)
+        .json(&payload)
+        .send()
+        .await
+        .with_context(|| format!("failed to POST webhook notification to '{}'", config.url))?;
+
+    if !response.status().is_success() {
+        bail!(
+            "webhook endpoint '{}' returned status {}",
+            config.url,
+            response.status()
+        );
+    }
+
+    debug!("sent webhook notification for '{file}' to {}", config.url);
+
+    Ok(())
+}